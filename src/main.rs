@@ -1,17 +1,34 @@
+use midir::{MidiOutput, MidiOutputConnection};
 use rodio::source::{Buffered, Source};
 use rodio::{Decoder, OutputStream};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
-use std::sync::mpsc::{self, Receiver, Sender};
-use std::time::Duration;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
 
 use iced::widget::{button, column, container, row, text, Checkbox, Radio, Slider};
-use iced::{executor, time, Alignment, Application, Command, Element, Length, Settings, Theme};
+use iced::{
+    executor, time, window, Alignment, Application, Command, Element, Event, Length, Settings,
+    Theme,
+};
 use iced_futures::Subscription;
 
 fn main() -> iced::Result {
-    Metronome::run(Settings::default())
+    Metronome::run(Settings {
+        flags: MetronomeSettings::load(),
+        ..Settings::default()
+    })
+}
+
+/// Resolves `~/.config/rust-metronome/config.toml` (or the platform
+/// equivalent). Returns `None` if the OS has no meaningful config directory,
+/// in which case settings simply aren't persisted.
+fn config_path() -> Option<PathBuf> {
+    let project_dirs = directories::ProjectDirs::from("", "", "rust-metronome")?;
+    Some(project_dirs.config_dir().join("config.toml"))
 }
 struct Metronome {
     value: u32,
@@ -21,9 +38,29 @@ struct Metronome {
     is_set_to_quack: bool,
     is_timer_on: bool,
     timer: Timer,
+    beats_per_measure: u32,
+    accents: Vec<bool>,
+    is_midi_clock_on: bool,
+    midi_ports: Vec<String>,
+    midi_port_index: usize,
+    sounds: HashMap<String, String>,
+    available_click_sounds: Vec<String>,
+    is_session_on: bool,
+    segments: Vec<Segment>,
+    session: Option<Session>,
+    is_trainer_on: bool,
+    trainer: TempoTrainerConfig,
+    player_events: Receiver<PlayerEvent>,
+}
+
+/// Notifications the player thread sends back to the GUI. Currently only
+/// used by the tempo trainer, whose bpm bumps happen on the thread's own
+/// bar-completion clock rather than in response to a GUI message.
+enum PlayerEvent {
+    BpmChanged(u32),
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 struct Timer {
     mins: u32,
     secs: u32,
@@ -35,18 +72,195 @@ impl Default for Timer {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// One stretch of a practice session: play (or rest, muting the click) for
+/// `duration` at `bpm`/`subdivision` before the session advances.
+#[derive(Clone, Serialize, Deserialize)]
+struct Segment {
+    label: String,
+    bpm: u32,
+    subdivision: Subdivision,
+    duration: Timer,
+    is_rest: bool,
+}
+
+/// Settings for the gradual tempo trainer: starting tempo, ceiling, and how
+/// quickly to ramp up between the two.
+#[derive(Clone, Serialize, Deserialize)]
+struct TempoTrainerConfig {
+    start_bpm: u32,
+    target_bpm: u32,
+    step: u32,
+    bars_per_increase: u32,
+}
+
+impl Default for TempoTrainerConfig {
+    fn default() -> Self {
+        Self {
+            start_bpm: 80,
+            target_bpm: 140,
+            step: 4,
+            bars_per_increase: 4,
+        }
+    }
+}
+
+fn default_segments() -> Vec<Segment> {
+    vec![
+        Segment {
+            label: "Work".to_string(),
+            bpm: 90,
+            subdivision: Subdivision::Quarter,
+            duration: Timer { mins: 2, secs: 0 },
+            is_rest: false,
+        },
+        Segment {
+            label: "Rest".to_string(),
+            bpm: 90,
+            subdivision: Subdivision::Quarter,
+            duration: Timer { mins: 0, secs: 30 },
+            is_rest: true,
+        },
+        Segment {
+            label: "Work".to_string(),
+            bpm: 100,
+            subdivision: Subdivision::Quarter,
+            duration: Timer { mins: 2, secs: 0 },
+            is_rest: false,
+        },
+    ]
+}
+
+/// Tracks progress through a practice session built from a template of
+/// `Segment`s.
+struct Session {
+    segments: Vec<Segment>,
+    index: usize,
+    remaining: Timer,
+}
+
+impl Session {
+    fn new(segments: Vec<Segment>) -> Option<Self> {
+        let remaining = segments.first()?.duration;
+        Some(Self {
+            segments,
+            index: 0,
+            remaining,
+        })
+    }
+
+    fn current(&self) -> &Segment {
+        &self.segments[self.index]
+    }
+}
+
+enum SessionTick {
+    Continue,
+    Advanced,
+    Finished,
+}
+
+/// Counts one second off the active segment, advancing to the next segment
+/// (or reporting completion) once it runs out, mirroring `update_timer`.
+fn tick_session(session: &mut Session) -> SessionTick {
+    if session.remaining.mins * 60 + session.remaining.secs > 0 {
+        session.remaining = update_timer(session.remaining);
+        return SessionTick::Continue;
+    }
+    if session.index + 1 < session.segments.len() {
+        session.index += 1;
+        session.remaining = session.segments[session.index].duration;
+        SessionTick::Advanced
+    } else {
+        SessionTick::Finished
+    }
+}
+
+/// A click subdivision expressed in pulses-per-quarter-note (PPQN), the same
+/// unit MIDI clock uses (24 pulses per quarter note). Triplets and dotted
+/// values fall out of this naturally since they don't divide evenly into
+/// straight eighths/sixteenths.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
 enum Subdivision {
     Quarter,
+    DottedEighth,
     Eighth,
+    EighthTriplet,
+    Sixteenth,
+    SixteenthTriplet,
+}
+
+impl Subdivision {
+    const ALL: [Subdivision; 6] = [
+        Subdivision::Quarter,
+        Subdivision::DottedEighth,
+        Subdivision::Eighth,
+        Subdivision::EighthTriplet,
+        Subdivision::Sixteenth,
+        Subdivision::SixteenthTriplet,
+    ];
+
+    fn pulses(self) -> u32 {
+        match self {
+            Subdivision::Quarter => 24,
+            Subdivision::DottedEighth => 18,
+            Subdivision::Eighth => 12,
+            Subdivision::EighthTriplet => 8,
+            Subdivision::Sixteenth => 6,
+            Subdivision::SixteenthTriplet => 4,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Subdivision::Quarter => "Quarter Note",
+            Subdivision::DottedEighth => "Dotted Eighth",
+            Subdivision::Eighth => "Eighth Note",
+            Subdivision::EighthTriplet => "Eighth Triplet",
+            Subdivision::Sixteenth => "Sixteenth Note",
+            Subdivision::SixteenthTriplet => "Sixteenth Triplet",
+        }
+    }
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
 struct MetronomeSettings {
     value: u32,
     is_set_to_quack: bool,
     is_timer_on: bool,
     subdivision: Option<Subdivision>,
     timer: Timer,
+    beats_per_measure: u32,
+    accents: Vec<bool>,
+    is_midi_clock_on: bool,
+    sounds: HashMap<String, String>,
+    is_session_on: bool,
+    segments: Vec<Segment>,
+    is_trainer_on: bool,
+    trainer: TempoTrainerConfig,
+}
+
+impl MetronomeSettings {
+    /// Loads settings from the config file, falling back to defaults if it's
+    /// missing, unreadable, or fails to parse.
+    fn load() -> Self {
+        config_path()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let Some(path) = config_path() else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let contents =
+            toml::to_string_pretty(self).expect("MetronomeSettings always serializes");
+        std::fs::write(path, contents)
+    }
 }
 
 impl Default for MetronomeSettings {
@@ -57,11 +271,41 @@ impl Default for MetronomeSettings {
             is_timer_on: false,
             subdivision: Some(Subdivision::Quarter),
             timer: Timer::default(),
+            beats_per_measure: 4,
+            accents: default_accents(4),
+            is_midi_clock_on: false,
+            sounds: default_sounds(),
+            is_session_on: false,
+            segments: default_segments(),
+            is_trainer_on: false,
+            trainer: TempoTrainerConfig::default(),
         }
     }
 }
 
-#[derive(PartialEq)]
+/// The default logical-name -> file-name mapping, resolved against
+/// `sounds_dir()` at load time.
+fn default_sounds() -> HashMap<String, String> {
+    HashMap::from([
+        ("accent".to_string(), "strong_beat.wav".to_string()),
+        ("click".to_string(), "weak_beat.wav".to_string()),
+        ("quack".to_string(), "duck_quack.wav".to_string()),
+        ("complete".to_string(), "session_complete.wav".to_string()),
+        ("trainer_cue".to_string(), "tempo_bump.wav".to_string()),
+    ])
+}
+
+/// Builds the default accent table for a measure of `beats_per_measure`
+/// beats: only the downbeat (beat 1) is accented.
+fn default_accents(beats_per_measure: u32) -> Vec<bool> {
+    let mut accents = vec![false; beats_per_measure as usize];
+    if let Some(downbeat) = accents.first_mut() {
+        *downbeat = true;
+    }
+    accents
+}
+
+#[derive(PartialEq, Clone, Copy)]
 enum MetronomeState {
     Stopped,
     Play,
@@ -70,7 +314,6 @@ enum MetronomeState {
 #[derive(Debug, Clone, Copy)]
 enum Message {
     Toggle,
-    Beat,
     IncrementBPM,
     DecrementBPM,
     SlideChangeBPM(u32),
@@ -78,11 +321,148 @@ enum Message {
     ToggleQuack(bool),
     Tick,
     ToggleTimer(bool),
+    IncrementBeatsPerMeasure,
+    DecrementBeatsPerMeasure,
+    ToggleAccent(usize),
+    ToggleMidiClock(bool),
+    CycleMidiPort,
+    SaveSettings,
+    CycleClickSound,
+    ToggleSession(bool),
+    ToggleTrainer(bool),
+    IncrementTrainerTarget,
+    DecrementTrainerTarget,
+    IncrementTrainerStep,
+    DecrementTrainerStep,
+    IncrementTrainerBars,
+    DecrementTrainerBars,
+    Exit,
 }
 
 enum Beat {
-    Beat,
-    Quack,
+    Start,
+    Stop,
+    SetBpm(u32),
+    SetSubdivision(Subdivision),
+    ToggleQuack(bool),
+    SetTimeSignature(u32),
+    SetAccents(Vec<bool>),
+    SetMidiEnabled(bool),
+    SetMidiPort(Option<String>),
+    SetSoundSources(HashMap<String, Buffered<Decoder<BufReader<File>>>>),
+    SetMuted(bool),
+    PlayOnce(String),
+    SetTempoTrainer(Option<TempoTrainerConfig>),
+}
+
+/// How far ahead of the current instant the player thread will schedule and
+/// sleep for a beat. Keeping this small bounds audible latency after a
+/// `SetBpm`/`Start` while still letting the thread sleep instead of spinning.
+const LOOK_AHEAD: Duration = Duration::from_millis(25);
+
+const MIDI_CLOCK: u8 = 0xF8;
+const MIDI_START: u8 = 0xFA;
+const MIDI_STOP: u8 = 0xFC;
+
+/// MIDI clock runs at a fixed 24 pulses per quarter note, independent of
+/// whatever subdivision the audible click is set to.
+fn midi_pulse_period(bpm: u32) -> Duration {
+    Duration::from_secs_f64(60. / bpm as f64 / 24.)
+}
+
+fn list_midi_port_names() -> Vec<String> {
+    match MidiOutput::new("Rust Metronome") {
+        Ok(midi_out) => midi_out
+            .ports()
+            .iter()
+            .filter_map(|port| midi_out.port_name(port).ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn open_midi_connection(port_name: &str) -> Option<MidiOutputConnection> {
+    let midi_out = MidiOutput::new("Rust Metronome").ok()?;
+    let port = midi_out.ports().into_iter().find(|port| {
+        midi_out
+            .port_name(port)
+            .map(|name| name == port_name)
+            .unwrap_or(false)
+    })?;
+    match midi_out.connect(&port, "rust-metronome-clock") {
+        Ok(conn) => Some(conn),
+        Err(err) => {
+            eprintln!("Failed to open MIDI port {port_name}: {err}");
+            None
+        }
+    }
+}
+
+fn beat_period(bpm: u32, subdivision: Subdivision) -> Duration {
+    let quarter_period = 60. / bpm as f64;
+    Duration::from_secs_f64(quarter_period * subdivision.pulses() as f64 / 24.)
+}
+
+impl Metronome {
+    fn send_time_signature(&self) {
+        self.player_thread
+            .send(Beat::SetTimeSignature(self.beats_per_measure))
+            .unwrap();
+        self.player_thread
+            .send(Beat::SetAccents(self.accents.clone()))
+            .unwrap();
+    }
+
+    fn to_settings(&self) -> MetronomeSettings {
+        MetronomeSettings {
+            value: self.value,
+            is_set_to_quack: self.is_set_to_quack,
+            is_timer_on: self.is_timer_on,
+            subdivision: self.subdivision,
+            timer: self.timer,
+            beats_per_measure: self.beats_per_measure,
+            accents: self.accents.clone(),
+            is_midi_clock_on: self.is_midi_clock_on,
+            sounds: self.sounds.clone(),
+            is_session_on: self.is_session_on,
+            segments: self.segments.clone(),
+            is_trainer_on: self.is_trainer_on,
+            trainer: self.trainer.clone(),
+        }
+    }
+
+    fn apply_segment(&mut self, segment: &Segment) {
+        self.value = segment.bpm;
+        self.subdivision = Some(segment.subdivision);
+        self.player_thread.send(Beat::SetBpm(segment.bpm)).unwrap();
+        self.player_thread
+            .send(Beat::SetSubdivision(segment.subdivision))
+            .unwrap();
+        self.player_thread.send(Beat::SetMuted(segment.is_rest)).unwrap();
+    }
+
+    /// Re-sends the tempo trainer config while it's enabled. Note this also
+    /// resets the ramp back to `start_bpm` on the player thread, so tweaking
+    /// a parameter mid-run restarts the ramp rather than patching it in place.
+    fn send_trainer(&self) {
+        if self.is_trainer_on {
+            self.player_thread
+                .send(Beat::SetTempoTrainer(Some(self.trainer.clone())))
+                .unwrap();
+        }
+    }
+}
+
+/// Backstop for exit paths that do unwind the stack. winit's event loop
+/// normally tears the process down directly rather than returning control
+/// here, so `Message::Exit` (triggered by `window::Event::CloseRequested`)
+/// is the save path that actually runs when the window is closed.
+impl Drop for Metronome {
+    fn drop(&mut self) {
+        if let Err(err) = self.to_settings().save() {
+            eprintln!("Failed to save settings: {err}");
+        }
+    }
 }
 
 impl Application for Metronome {
@@ -92,47 +472,95 @@ impl Application for Metronome {
     type Theme = Theme;
 
     fn new(flags: MetronomeSettings) -> (Metronome, Command<Self::Message>) {
-        let sound_sources = read_sounds_into_buffer();
+        let sound_sources = read_sounds_into_buffer(&flags.sounds);
         let (tx, rx) = mpsc::channel();
-        std::thread::spawn(move || player_thread(sound_sources, rx));
+        let (event_tx, event_rx) = mpsc::channel();
+        std::thread::spawn(move || player_thread(sound_sources, rx, event_tx));
+        tx.send(Beat::SetBpm(flags.value)).unwrap();
+        tx.send(Beat::SetSubdivision(
+            flags.subdivision.unwrap_or(Subdivision::Quarter),
+        ))
+        .unwrap();
+        tx.send(Beat::ToggleQuack(flags.is_set_to_quack)).unwrap();
+        tx.send(Beat::SetTimeSignature(flags.beats_per_measure))
+            .unwrap();
+        tx.send(Beat::SetAccents(flags.accents.clone())).unwrap();
+
+        let midi_ports = list_midi_port_names();
+        tx.send(Beat::SetMidiPort(midi_ports.first().cloned()))
+            .unwrap();
+        tx.send(Beat::SetMidiEnabled(flags.is_midi_clock_on))
+            .unwrap();
+
+        let available_click_sounds = list_sound_files();
+
+        let session = flags.is_session_on.then(|| Session::new(flags.segments.clone())).flatten();
+        let is_trainer_on = flags.is_trainer_on && session.is_none();
+        let (value, subdivision) = match &session {
+            Some(session) => (session.current().bpm, Some(session.current().subdivision)),
+            None if is_trainer_on => (flags.trainer.start_bpm, flags.subdivision),
+            None => (flags.value, flags.subdivision),
+        };
+        tx.send(Beat::SetBpm(value)).unwrap();
+        tx.send(Beat::SetSubdivision(subdivision.unwrap_or(Subdivision::Quarter)))
+            .unwrap();
+        tx.send(Beat::SetMuted(
+            session.as_ref().map(|s| s.current().is_rest).unwrap_or(false),
+        ))
+        .unwrap();
+        tx.send(Beat::SetTempoTrainer(
+            is_trainer_on.then(|| flags.trainer.clone()),
+        ))
+        .unwrap();
+
         (
             Self {
-                value: flags.value,
+                value,
                 state: MetronomeState::Stopped,
                 player_thread: tx,
-                subdivision: flags.subdivision,
+                subdivision,
                 is_set_to_quack: flags.is_set_to_quack,
                 is_timer_on: flags.is_timer_on,
                 timer: flags.timer,
+                beats_per_measure: flags.beats_per_measure,
+                accents: flags.accents,
+                is_midi_clock_on: flags.is_midi_clock_on,
+                midi_ports,
+                midi_port_index: 0,
+                sounds: flags.sounds,
+                available_click_sounds,
+                is_session_on: flags.is_session_on,
+                segments: flags.segments,
+                session,
+                is_trainer_on,
+                trainer: flags.trainer,
+                player_events: event_rx,
             },
             Command::none(),
         )
     }
 
     fn subscription(&self) -> iced::Subscription<Self::Message> {
-        // This factor will determine how fast to play the click
-        let subdivision_factor = match self.subdivision.unwrap() {
-            Subdivision::Quarter => 1.,
-            Subdivision::Eighth => 2.,
-        };
-
-        let mut subscriptions: Vec<Subscription<_>> = vec![];
-        let click_sub = time::every(Duration::from_secs_f64(
-            60. / self.value as f64 / subdivision_factor,
-        ))
-        .map(|_| Message::Beat);
+        // Beat timing now lives in `player_thread`'s own look-ahead clock; the
+        // GUI only needs a subscription to tick the on-screen practice timer,
+        // advance the practice session, and poll for tempo trainer bpm bumps.
         let timer_sub = time::every(Duration::from_secs(1)).map(|_| Message::Tick);
 
-        subscriptions.push(click_sub);
-        // Timer should only tick if the checkbox is toggled
-        if self.is_timer_on {
-            subscriptions.push(timer_sub);
-        }
+        let needs_tick = self.is_timer_on || self.is_session_on || self.is_trainer_on;
+        let tick_sub = match (self.state, needs_tick) {
+            (MetronomeState::Play, true) => timer_sub,
+            _ => iced::Subscription::none(),
+        };
 
-        match self.state {
-            MetronomeState::Play => Subscription::batch(subscriptions),
-            MetronomeState::Stopped => iced::Subscription::none(),
-        }
+        // winit tears the process down directly on window close rather than
+        // unwinding back through `Application::run`, so `Drop` can't be
+        // trusted to save settings; catch the close request here instead.
+        let close_sub = iced::subscription::events_with(|event, _status| match event {
+            Event::Window(window::Event::CloseRequested) => Some(Message::Exit),
+            _ => None,
+        });
+
+        iced::Subscription::batch([tick_sub, close_sub])
     }
 
     fn title(&self) -> String {
@@ -164,25 +592,91 @@ impl Application for Metronome {
             )
             .on_press(Message::Toggle)
             .width(75),
+            row(Subdivision::ALL
+                .iter()
+                .map(|&subdivision| {
+                    Radio::new(
+                        subdivision.label(),
+                        subdivision,
+                        self.subdivision,
+                        Message::ChangeSubdivision,
+                    )
+                    .into()
+                })
+                .collect::<Vec<_>>())
+            .spacing(20),
             row![
-                Radio::new(
-                    "Quarter Note",
-                    Subdivision::Quarter,
-                    self.subdivision,
-                    Message::ChangeSubdivision
-                ),
-                Radio::new(
-                    "Eighth Note",
-                    Subdivision::Eighth,
-                    self.subdivision,
-                    Message::ChangeSubdivision
-                )
+                button(container("-").width(Length::Fill).center_x())
+                    .on_press(Message::DecrementBeatsPerMeasure)
+                    .width(35),
+                text(format!("{} beats/bar", self.beats_per_measure)),
+                button(container("+").width(Length::Fill).center_x())
+                    .on_press(Message::IncrementBeatsPerMeasure)
+                    .width(35),
             ]
-            .spacing(20),
+            .spacing(10),
+            row(self
+                .accents
+                .iter()
+                .enumerate()
+                .map(|(i, &accented)| {
+                    Checkbox::new(format!("{}", i + 1), accented, move |_| {
+                        Message::ToggleAccent(i)
+                    })
+                    .into()
+                })
+                .collect::<Vec<_>>())
+            .spacing(10),
             row![
                 Checkbox::new("Timer ", self.is_timer_on, Message::ToggleTimer),
                 text(format_mins_and_secs(self.timer.mins, self.timer.secs))
             ],
+            row![
+                Checkbox::new("Practice Session", self.is_session_on, Message::ToggleSession),
+                text(match &self.session {
+                    Some(session) => format!(
+                        "{} {}",
+                        session.current().label,
+                        format_mins_and_secs(session.remaining.mins, session.remaining.secs)
+                    ),
+                    None => format!("{} segments", self.segments.len()),
+                })
+            ]
+            .spacing(10),
+            row![Checkbox::new("Tempo Trainer", self.is_trainer_on, Message::ToggleTrainer)],
+            row![
+                text("Target:"),
+                button(container("-").width(Length::Fill).center_x())
+                    .on_press(Message::DecrementTrainerTarget)
+                    .width(35),
+                text(self.trainer.target_bpm),
+                button(container("+").width(Length::Fill).center_x())
+                    .on_press(Message::IncrementTrainerTarget)
+                    .width(35),
+            ]
+            .spacing(10),
+            row![
+                text("Step:"),
+                button(container("-").width(Length::Fill).center_x())
+                    .on_press(Message::DecrementTrainerStep)
+                    .width(35),
+                text(self.trainer.step),
+                button(container("+").width(Length::Fill).center_x())
+                    .on_press(Message::IncrementTrainerStep)
+                    .width(35),
+            ]
+            .spacing(10),
+            row![
+                text("Bars:"),
+                button(container("-").width(Length::Fill).center_x())
+                    .on_press(Message::DecrementTrainerBars)
+                    .width(35),
+                text(self.trainer.bars_per_increase),
+                button(container("+").width(Length::Fill).center_x())
+                    .on_press(Message::IncrementTrainerBars)
+                    .width(35),
+            ]
+            .spacing(10),
             row![Checkbox::new(
                 if !self.is_set_to_quack {
                     "Set to Quack"
@@ -191,7 +685,42 @@ impl Application for Metronome {
                 },
                 self.is_set_to_quack,
                 Message::ToggleQuack
-            )]
+            )],
+            row![
+                Checkbox::new("MIDI Clock", self.is_midi_clock_on, Message::ToggleMidiClock),
+                button(
+                    container(
+                        self.midi_ports
+                            .get(self.midi_port_index)
+                            .map(String::as_str)
+                            .unwrap_or("(no MIDI ports)")
+                    )
+                    .width(Length::Fill)
+                    .center_x()
+                )
+                .on_press(Message::CycleMidiPort)
+                .width(160),
+            ]
+            .spacing(20),
+            row![
+                text("Click Sound:"),
+                button(
+                    container(
+                        self.sounds
+                            .get("click")
+                            .map(String::as_str)
+                            .unwrap_or("(none)")
+                    )
+                    .width(Length::Fill)
+                    .center_x()
+                )
+                .on_press(Message::CycleClickSound)
+                .width(160),
+            ]
+            .spacing(10),
+            button(container("Save Settings").width(Length::Fill).center_x())
+                .on_press(Message::SaveSettings)
+                .width(150)
         ]
         .spacing(10)
         .padding(20)
@@ -208,60 +737,227 @@ impl Application for Metronome {
     fn update(&mut self, message: Message) -> Command<Self::Message> {
         match message {
             Message::IncrementBPM => {
-                self.value += 1;
+                // The tempo trainer owns `bpm` on the player thread while it's
+                // running; manual nudges here would just get stomped by (or
+                // stomp) its ramp, so they're disabled until it's switched off.
+                if !self.is_trainer_on {
+                    self.value += 1;
+                    self.player_thread.send(Beat::SetBpm(self.value)).unwrap();
+                }
                 Command::none()
             }
             Message::DecrementBPM => {
-                self.value -= 1;
+                if !self.is_trainer_on {
+                    self.value -= 1;
+                    self.player_thread.send(Beat::SetBpm(self.value)).unwrap();
+                }
                 Command::none()
             }
             Message::Toggle => {
                 if self.state == MetronomeState::Stopped {
                     println!("Playing metronome!");
                     self.state = MetronomeState::Play;
-                    Command::perform(async {}, |()| Message::Beat)
+                    self.player_thread.send(Beat::Start).unwrap();
                 } else {
                     println!("Stopping Metronome");
                     self.state = MetronomeState::Stopped;
-                    Command::none()
+                    self.player_thread.send(Beat::Stop).unwrap();
                 }
-            }
-            Message::Beat => {
-                match self.state {
-                    MetronomeState::Play => {
-                        if self.is_set_to_quack {
-                            self.player_thread.send(Beat::Quack).unwrap();
-                        } else {
-                            self.player_thread.send(Beat::Beat).unwrap();
-                        }
-                    }
-                    MetronomeState::Stopped => {}
-                };
                 Command::none()
             }
             Message::SlideChangeBPM(bpm) => {
-                self.value = bpm;
+                if !self.is_trainer_on {
+                    self.value = bpm;
+                    self.player_thread.send(Beat::SetBpm(self.value)).unwrap();
+                }
                 Command::none()
             }
             Message::ChangeSubdivision(subdivision) => {
                 self.subdivision = Some(subdivision);
+                self.player_thread
+                    .send(Beat::SetSubdivision(subdivision))
+                    .unwrap();
                 Command::none()
             }
             Message::ToggleQuack(should_quack) => {
                 self.is_set_to_quack = should_quack;
+                self.player_thread
+                    .send(Beat::ToggleQuack(should_quack))
+                    .unwrap();
                 Command::none()
             }
             Message::Tick => {
-                // Timer should not overflow into negative seconds
-                if (self.timer.mins * 60 + self.timer.secs) > 0 {
+                // Timer should only count down while the Timer feature is on,
+                // and should not overflow into negative seconds.
+                if self.is_timer_on && (self.timer.mins * 60 + self.timer.secs) > 0 {
                     self.timer = update_timer(self.timer);
                 }
+                if let Some(session) = &mut self.session {
+                    match tick_session(session) {
+                        SessionTick::Continue => {}
+                        SessionTick::Advanced => {
+                            let segment = session.current().clone();
+                            self.apply_segment(&segment);
+                        }
+                        SessionTick::Finished => {
+                            self.is_session_on = false;
+                            self.session = None;
+                            self.state = MetronomeState::Stopped;
+                            self.player_thread.send(Beat::Stop).unwrap();
+                            self.player_thread.send(Beat::SetMuted(false)).unwrap();
+                            self.player_thread
+                                .send(Beat::PlayOnce("complete".to_string()))
+                                .unwrap();
+                        }
+                    }
+                }
+                while let Ok(PlayerEvent::BpmChanged(new_bpm)) = self.player_events.try_recv() {
+                    self.value = new_bpm;
+                }
                 Command::none()
             }
             Message::ToggleTimer(toggle_timer) => {
                 self.is_timer_on = toggle_timer;
                 Command::none()
             }
+            Message::IncrementBeatsPerMeasure => {
+                self.beats_per_measure += 1;
+                self.accents.resize(self.beats_per_measure as usize, false);
+                self.send_time_signature();
+                Command::none()
+            }
+            Message::DecrementBeatsPerMeasure => {
+                if self.beats_per_measure > 1 {
+                    self.beats_per_measure -= 1;
+                    self.accents.resize(self.beats_per_measure as usize, false);
+                    self.send_time_signature();
+                }
+                Command::none()
+            }
+            Message::ToggleAccent(index) => {
+                if let Some(accent) = self.accents.get_mut(index) {
+                    *accent = !*accent;
+                }
+                self.player_thread
+                    .send(Beat::SetAccents(self.accents.clone()))
+                    .unwrap();
+                Command::none()
+            }
+            Message::ToggleMidiClock(enabled) => {
+                self.is_midi_clock_on = enabled;
+                self.player_thread.send(Beat::SetMidiEnabled(enabled)).unwrap();
+                Command::none()
+            }
+            Message::CycleMidiPort => {
+                if !self.midi_ports.is_empty() {
+                    self.midi_port_index = (self.midi_port_index + 1) % self.midi_ports.len();
+                    self.player_thread
+                        .send(Beat::SetMidiPort(
+                            self.midi_ports.get(self.midi_port_index).cloned(),
+                        ))
+                        .unwrap();
+                }
+                Command::none()
+            }
+            Message::SaveSettings => {
+                if let Err(err) = self.to_settings().save() {
+                    eprintln!("Failed to save settings: {err}");
+                }
+                Command::none()
+            }
+            Message::CycleClickSound => {
+                if !self.available_click_sounds.is_empty() {
+                    let current = self.sounds.get("click").cloned();
+                    let current_index = current
+                        .and_then(|current| self.available_click_sounds.iter().position(|f| *f == current))
+                        .unwrap_or(0);
+                    let next_index = (current_index + 1) % self.available_click_sounds.len();
+                    self.sounds.insert(
+                        "click".to_string(),
+                        self.available_click_sounds[next_index].clone(),
+                    );
+                    self.player_thread
+                        .send(Beat::SetSoundSources(read_sounds_into_buffer(&self.sounds)))
+                        .unwrap();
+                }
+                Command::none()
+            }
+            Message::ToggleSession(enabled) => {
+                self.is_session_on = enabled;
+                if enabled {
+                    self.is_trainer_on = false;
+                    self.player_thread.send(Beat::SetTempoTrainer(None)).unwrap();
+                    self.session = Session::new(self.segments.clone());
+                    if let Some(session) = &self.session {
+                        let segment = session.current().clone();
+                        self.apply_segment(&segment);
+                    }
+                } else {
+                    self.session = None;
+                    self.player_thread.send(Beat::SetMuted(false)).unwrap();
+                }
+                Command::none()
+            }
+            Message::ToggleTrainer(enabled) => {
+                self.is_trainer_on = enabled;
+                if enabled {
+                    self.is_session_on = false;
+                    self.session = None;
+                    self.trainer.start_bpm = self.value;
+                    // The ramp never fires if target_bpm isn't above
+                    // start_bpm (e.g. the user slid the tempo past the old
+                    // target before enabling the trainer); keep it a real ramp.
+                    if self.trainer.target_bpm <= self.trainer.start_bpm {
+                        self.trainer.target_bpm = self.trainer.start_bpm + self.trainer.step.max(1);
+                    }
+                    self.send_trainer();
+                } else {
+                    self.player_thread.send(Beat::SetTempoTrainer(None)).unwrap();
+                }
+                Command::none()
+            }
+            Message::IncrementTrainerTarget => {
+                self.trainer.target_bpm += 1;
+                self.send_trainer();
+                Command::none()
+            }
+            Message::DecrementTrainerTarget => {
+                if self.trainer.target_bpm > self.trainer.start_bpm {
+                    self.trainer.target_bpm -= 1;
+                    self.send_trainer();
+                }
+                Command::none()
+            }
+            Message::IncrementTrainerStep => {
+                self.trainer.step += 1;
+                self.send_trainer();
+                Command::none()
+            }
+            Message::DecrementTrainerStep => {
+                if self.trainer.step > 1 {
+                    self.trainer.step -= 1;
+                    self.send_trainer();
+                }
+                Command::none()
+            }
+            Message::IncrementTrainerBars => {
+                self.trainer.bars_per_increase += 1;
+                self.send_trainer();
+                Command::none()
+            }
+            Message::DecrementTrainerBars => {
+                if self.trainer.bars_per_increase > 1 {
+                    self.trainer.bars_per_increase -= 1;
+                    self.send_trainer();
+                }
+                Command::none()
+            }
+            Message::Exit => {
+                if let Err(err) = self.to_settings().save() {
+                    eprintln!("Failed to save settings: {err}");
+                }
+                window::close()
+            }
         }
     }
 }
@@ -284,51 +980,250 @@ fn update_timer(mut timer: Timer) -> Timer {
     timer
 }
 
+/// Owns beat timing. Rather than sleeping for a full period every loop (which
+/// accumulates rounding error), it accumulates `next_beat` forward from a
+/// fixed epoch and only sleeps until just before that instant falls inside
+/// `LOOK_AHEAD`, so drift can't build up across a long session.
 fn player_thread(
-    sound_sources: HashMap<String, Buffered<Decoder<BufReader<File>>>>,
+    mut sound_sources: HashMap<String, Buffered<Decoder<BufReader<File>>>>,
     rx: Receiver<Beat>,
+    event_tx: Sender<PlayerEvent>,
 ) {
     let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-    while let Ok(beat) = rx.recv() {
-        match beat {
-            Beat::Beat => {
-                let click_sound = sound_sources.get(&"click".to_string()).unwrap();
-                stream_handle
-                    .play_raw(click_sound.clone().convert_samples())
-                    .unwrap();
+
+    let mut playing = false;
+    let mut bpm: u32 = 120;
+    let mut subdivision = Subdivision::Quarter;
+    let mut is_set_to_quack = false;
+    let mut muted = false;
+    let mut next_beat = Instant::now();
+    let mut beats_per_measure: u32 = 4;
+    let mut accents = default_accents(beats_per_measure);
+    let mut beat_index: u32 = 0;
+    let mut trainer: Option<TempoTrainerConfig> = None;
+    let mut trainer_bar_count: u32 = 0;
+
+    let mut midi_enabled = false;
+    let mut midi_port: Option<String> = None;
+    let mut midi_conn: Option<MidiOutputConnection> = None;
+    let mut next_midi_pulse = Instant::now();
+
+    loop {
+        let next_deadline = [
+            playing.then_some(next_beat),
+            (playing && midi_conn.is_some()).then_some(next_midi_pulse),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+
+        let wait = match next_deadline {
+            Some(deadline) => deadline
+                .saturating_duration_since(Instant::now())
+                .saturating_sub(LOOK_AHEAD),
+            // Nothing pending; block until the next control message.
+            None => Duration::from_secs(60 * 60),
+        };
+
+        match rx.recv_timeout(wait) {
+            Ok(Beat::Start) => {
+                playing = true;
+                next_beat = Instant::now();
+                next_midi_pulse = Instant::now();
+                beat_index = 0;
+                trainer_bar_count = 0;
+                if midi_enabled {
+                    if let Some(conn) = &mut midi_conn {
+                        let _ = conn.send(&[MIDI_START]);
+                    }
+                }
             }
-            Beat::Quack => {
-                let quack_sound = sound_sources.get(&"quack".to_string()).unwrap();
-                stream_handle
-                    .play_raw(quack_sound.clone().convert_samples())
-                    .unwrap();
+            Ok(Beat::Stop) => {
+                playing = false;
+                if midi_enabled {
+                    if let Some(conn) = &mut midi_conn {
+                        let _ = conn.send(&[MIDI_STOP]);
+                    }
+                }
+            }
+            Ok(Beat::SetBpm(new_bpm)) => bpm = new_bpm,
+            Ok(Beat::SetSubdivision(new_subdivision)) => subdivision = new_subdivision,
+            Ok(Beat::ToggleQuack(should_quack)) => is_set_to_quack = should_quack,
+            Ok(Beat::SetTimeSignature(new_beats_per_measure)) => {
+                beats_per_measure = new_beats_per_measure.max(1);
+                beat_index %= beats_per_measure;
+            }
+            Ok(Beat::SetAccents(new_accents)) => accents = new_accents,
+            Ok(Beat::SetMidiEnabled(enabled)) => {
+                midi_enabled = enabled;
+                if midi_enabled && midi_conn.is_none() {
+                    midi_conn = midi_port.as_deref().and_then(open_midi_connection);
+                } else if !midi_enabled {
+                    midi_conn = None;
+                }
             }
+            Ok(Beat::SetMidiPort(new_port)) => {
+                midi_conn = if midi_enabled {
+                    new_port.as_deref().and_then(open_midi_connection)
+                } else {
+                    None
+                };
+                midi_port = new_port;
+            }
+            Ok(Beat::SetSoundSources(new_sound_sources)) => sound_sources = new_sound_sources,
+            Ok(Beat::SetMuted(new_muted)) => muted = new_muted,
+            Ok(Beat::SetTempoTrainer(new_trainer)) => {
+                trainer_bar_count = 0;
+                if let Some(cfg) = &new_trainer {
+                    bpm = cfg.start_bpm;
+                    let _ = event_tx.send(PlayerEvent::BpmChanged(bpm));
+                }
+                trainer = new_trainer;
+            }
+            Ok(Beat::PlayOnce(sound_name)) => {
+                if let Some(sound) = sound_sources.get(&sound_name) {
+                    stream_handle
+                        .play_raw(sound.clone().convert_samples())
+                        .unwrap();
+                } else {
+                    eprintln!("Warning: no sound loaded for '{sound_name}'");
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
         }
-    }
-}
-fn read_sounds_into_buffer() -> HashMap<String, Buffered<Decoder<BufReader<File>>>> {
-    let mut sound_sources: HashMap<String, Buffered<Decoder<BufReader<File>>>> = HashMap::new();
 
-    let click_file = BufReader::new(
-        File::open(
-            "/Users/Mattdamachine/Code/Rust/practice/rodio_practice/rust_metronome/media/strong_beat.wav",
-        )
-        .unwrap(),
-    );
-    let click_source = Decoder::new(click_file).unwrap().buffered();
+        if playing {
+            let now = Instant::now();
+            if next_beat <= now + LOOK_AHEAD {
+                if next_beat > now {
+                    std::thread::sleep(next_beat - now);
+                }
+                let is_accented = accents
+                    .get(beat_index as usize)
+                    .copied()
+                    .unwrap_or(beat_index == 0);
+                let sound_name = if is_set_to_quack {
+                    "quack"
+                } else if is_accented {
+                    "accent"
+                } else {
+                    "click"
+                };
+                if !muted {
+                    if let Some(sound) = sound_sources.get(sound_name) {
+                        stream_handle
+                            .play_raw(sound.clone().convert_samples())
+                            .unwrap();
+                    } else {
+                        eprintln!("Warning: no sound loaded for '{sound_name}', skipping beat");
+                    }
+                }
+                next_beat += beat_period(bpm, subdivision);
+                beat_index = (beat_index + 1) % beats_per_measure.max(1);
 
-    sound_sources.insert("click".to_string(), click_source);
+                if beat_index == 0 {
+                    if let Some(cfg) = &trainer {
+                        if bpm < cfg.target_bpm {
+                            trainer_bar_count += 1;
+                            if trainer_bar_count >= cfg.bars_per_increase.max(1) {
+                                trainer_bar_count = 0;
+                                bpm = (bpm + cfg.step).min(cfg.target_bpm);
+                                let _ = event_tx.send(PlayerEvent::BpmChanged(bpm));
+                                if !muted {
+                                    if let Some(sound) = sound_sources.get("trainer_cue") {
+                                        stream_handle
+                                            .play_raw(sound.clone().convert_samples())
+                                            .unwrap();
+                                    } else {
+                                        eprintln!("Warning: no sound loaded for 'trainer_cue'");
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
 
-    let quack_file = BufReader::new(
-        File::open(
-            "/Users/Mattdamachine/Code/Rust/practice/rodio_practice/rust_metronome/media/duck_quack.wav",
-        )
-        .unwrap(),
-    );
+            if midi_enabled {
+                let now = Instant::now();
+                if next_midi_pulse <= now + LOOK_AHEAD {
+                    if next_midi_pulse > now {
+                        std::thread::sleep(next_midi_pulse - now);
+                    }
+                    if let Some(conn) = &mut midi_conn {
+                        let _ = conn.send(&[MIDI_CLOCK]);
+                    }
+                    next_midi_pulse += midi_pulse_period(bpm);
+                }
+            }
+        }
+    }
+}
+/// Where click samples live: a `sounds/` directory next to the executable if
+/// one is present there, otherwise the one alongside the config file.
+fn sounds_dir() -> PathBuf {
+    let next_to_exe = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join("sounds")));
+    if let Some(dir) = &next_to_exe {
+        if dir.is_dir() {
+            return dir.clone();
+        }
+    }
+    config_path()
+        .and_then(|path| path.parent().map(|dir| dir.join("sounds")))
+        .unwrap_or_else(|| PathBuf::from("sounds"))
+}
 
-    let quack_source = Decoder::new(quack_file).unwrap().buffered();
+/// Lists the `.wav`/`.mp3` files available in `sounds_dir()`, for the
+/// runtime click-sound picker.
+fn list_sound_files() -> Vec<String> {
+    let dir = sounds_dir();
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .map(|ext| ext.eq_ignore_ascii_case("wav") || ext.eq_ignore_ascii_case("mp3"))
+                .unwrap_or(false)
+        })
+        .filter_map(|path| path.file_name()?.to_str().map(String::from))
+        .collect();
+    files.sort();
+    files
+}
 
-    sound_sources.insert("quack".to_string(), quack_source);
+fn load_sound(path: &std::path::Path) -> Result<Buffered<Decoder<BufReader<File>>>, String> {
+    let file = BufReader::new(File::open(path).map_err(|err| err.to_string())?);
+    Decoder::new(file)
+        .map(Source::buffered)
+        .map_err(|err| err.to_string())
+}
 
+/// Loads every sound in `sounds` (a logical name, e.g. `click`, mapped to a
+/// file name under `sounds_dir()`). A sound that fails to load is skipped
+/// with a warning rather than panicking, so a missing/renamed file doesn't
+/// take down the whole player thread.
+fn read_sounds_into_buffer(
+    sounds: &HashMap<String, String>,
+) -> HashMap<String, Buffered<Decoder<BufReader<File>>>> {
+    let dir = sounds_dir();
+    let mut sound_sources = HashMap::new();
+    for (name, file_name) in sounds {
+        let path = dir.join(file_name);
+        match load_sound(&path) {
+            Ok(source) => {
+                sound_sources.insert(name.clone(), source);
+            }
+            Err(err) => {
+                eprintln!("Warning: couldn't load sound '{name}' from {path:?}: {err}");
+            }
+        }
+    }
     sound_sources
 }